@@ -0,0 +1,136 @@
+//! Validation pass for `ta-agi-doorbell --check-config`.
+//!
+//! Operators can verify a config before deploying it, instead of finding out
+//! about problems only when `main` panics on startup. On top of the parsing
+//! [Config::create] already does, we detect duplicate door names, out-of-range
+//! PDO indices, duplicate CMI targets and probe each distinct CMI host for
+//! reachability, then print a per-door table.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+use tracing::error;
+
+use crate::config::Config;
+
+/// Largest PDO index a TA CMI accepts (one-based, per virtual CAN node).
+const MAX_PDO: u16 = 64;
+
+/// Run the config diagnostics and return the process exit code.
+///
+/// Returns `1` when a hard error (unparsable config, duplicate door names or a
+/// PDO-zero entry) is present, otherwise `0`.
+pub async fn check_config() -> i32 {
+    let config = match Config::create() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Config is invalid: {e}");
+            return 1;
+        }
+    };
+
+    let doors = config.cmi.door_mappings();
+    let mut hard_error = false;
+
+    // Detect duplicate door names -- these make `get_cmi_for_door` ambiguous.
+    let mut name_counts: HashMap<&str, usize> = HashMap::new();
+    for door in doors {
+        *name_counts.entry(door.door_name.as_str()).or_default() += 1;
+    }
+
+    // Detect two doors driving the same CMI output.
+    let mut target_names: HashMap<_, Vec<&str>> = HashMap::new();
+    for door in doors {
+        target_names
+            .entry(door.target())
+            .or_default()
+            .push(door.door_name.as_str());
+    }
+
+    // Probe each *distinct* CMI host once, rather than re-probing duplicates.
+    let mut probes: HashMap<String, String> = HashMap::new();
+    for door in doors {
+        let host = door.cmi_host();
+        if !probes.contains_key(&host) {
+            let result = match probe_cmi(&host).await {
+                Ok(()) => "responded".to_string(),
+                Err(ProbeError::Io(e)) => format!("unreachable: {e}"),
+                Err(ProbeError::NoResponse) => "no ICMP error (expected; CoE is one-way)".to_string(),
+            };
+            probes.insert(host, result);
+        }
+    }
+
+    println!("{:<24} {:<24} {:<8} {}", "door", "cmi", "pdo", "probe");
+    for door in doors {
+        let mut notes = Vec::new();
+        if name_counts.get(door.door_name.as_str()).copied().unwrap_or(0) > 1 {
+            notes.push("duplicate door_name".to_string());
+            hard_error = true;
+        }
+        if door.pdo_one_based() > MAX_PDO {
+            notes.push(format!(
+                "pdo {} out of range (1..={MAX_PDO})",
+                door.pdo_one_based()
+            ));
+        }
+        if target_names
+            .get(&door.target())
+            .map(|names| names.len() > 1)
+            .unwrap_or(false)
+        {
+            notes.push("duplicate CMI target".to_string());
+        }
+        if let Some(probe) = probes.get(&door.cmi_host()) {
+            notes.push(probe.clone());
+        }
+        println!(
+            "{:<24} {:<24} {:<8} {}",
+            door.door_name,
+            door.cmi_host(),
+            door.pdo_one_based(),
+            notes.join(", ")
+        );
+    }
+
+    if hard_error {
+        error!("Config has hard errors, see the table above");
+        1
+    } else {
+        0
+    }
+}
+
+/// Outcome of actually probing a CMI host.
+enum ProbeError {
+    /// The socket setup or send itself failed, or an ICMP error (e.g. host
+    /// unreachable / port unreachable) came back on the connected socket.
+    Io(std::io::Error),
+    /// The probe datagram went out without error but nothing came back within
+    /// the timeout. TA CoE is a one-way protocol, so this is inconclusive
+    /// rather than a hard failure.
+    NoResponse,
+}
+
+/// Send a one-byte probe datagram to `host` and watch for an error.
+///
+/// Unlike merely `connect`-ing a connectionless socket (which contacts nothing),
+/// this connects, sends a datagram, and does a short `recv`. A powered-off or
+/// wrong-addressed CMI surfaces as an ICMP error on the connected socket, which
+/// shows up as [ProbeError::Io]; a silent host yields [ProbeError::NoResponse].
+async fn probe_cmi(host: &str) -> Result<(), ProbeError> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(ProbeError::Io)?;
+    socket.connect(host).await.map_err(ProbeError::Io)?;
+    // Send a minimal probe datagram. This is not a valid CoE frame -- CoE is
+    // one-way so a healthy CMI never replies anyway; the point is purely to
+    // trigger an ICMP error on the connected socket if the host is down.
+    socket.send(&[0_u8]).await.map_err(ProbeError::Io)?;
+    let mut buf = [0_u8; 16];
+    match timeout(Duration::from_millis(500), socket.recv(&mut buf)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(ProbeError::Io(e)),
+        Err(_) => Err(ProbeError::NoResponse),
+    }
+}
@@ -0,0 +1,61 @@
+//! Incoming CoE listener subsystem.
+//!
+//! The service otherwise only ever *sends* CoE values, so it cannot answer "is
+//! the door currently open?". This module binds a UDP socket and continuously
+//! receives the CoE packets a TA CMI can emit, caching the latest [COEValue] per
+//! `(virtual_node, pdo)` so the `/door_status/:door` route can report it back to
+//! the dialplan.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use coe::{COEValue, Packet};
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+
+/// Latest received [COEValue] per `(virtual_node, pdo)`, shared between the
+/// receive loop and the door-status handler.
+pub type SharedDoorState = Arc<RwLock<HashMap<(u8, u8), COEValue>>>;
+
+/// Create an empty, shareable door-state cache.
+pub fn new_state() -> SharedDoorState {
+    Arc::new(RwLock::new(HashMap::new()))
+}
+
+/// Bind the CoE listener on `port` and run the receive loop forever.
+///
+/// Malformed packets are logged at `warn` and skipped rather than aborting the
+/// loop, so a single bad datagram cannot take the listener down.
+pub async fn listen(port: u16, state: SharedDoorState) {
+    let socket = match UdpSocket::bind(("0.0.0.0", port)).await {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Could not bind CoE listener on port {port}, door state will be unavailable: {e}");
+            return;
+        }
+    };
+    debug!("CoE listener bound on port {port}");
+
+    let mut buf = [0_u8; 1472];
+    loop {
+        let len = match socket.recv_from(&mut buf).await {
+            Ok((len, _)) => len,
+            Err(e) => {
+                warn!("Error receiving CoE packet: {e}");
+                continue;
+            }
+        };
+        let packet = match Packet::try_from(&buf[..len]) {
+            Ok(p) => p,
+            Err(e) => {
+                warn!("Could not parse incoming CoE packet: {e}");
+                continue;
+            }
+        };
+        let mut cache = state.write().await;
+        for payload in packet.payloads() {
+            cache.insert((payload.node(), payload.pdo()), payload.value());
+        }
+    }
+}
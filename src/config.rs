@@ -1,8 +1,18 @@
-use std::{fs::read_to_string, net::Ipv4Addr, path::Path};
+use std::{fs::read_to_string, net::Ipv4Addr, path::Path, time::Duration};
 
 use serde::Deserialize;
+use tokio::net::UdpSocket;
+use tokio::time::{sleep, timeout};
+use tracing::warn;
 
-use coe::{COEValue, Payload};
+use coe::{COEValue, Packet, Payload};
+
+/// Default number of extra attempts after the first send fails.
+const DEFAULT_RETRIES: u32 = 2;
+/// Default backoff between send attempts, in milliseconds.
+const DEFAULT_RETRY_DELAY_MS: u64 = 200;
+/// Default per-attempt send timeout, in milliseconds.
+const DEFAULT_SEND_TIMEOUT_MS: u64 = 1000;
 
 #[derive(Debug)]
 pub enum ConfigError {
@@ -114,10 +124,14 @@ impl From<AgiConfigData> for AgiConfig {
     }
 }
 
+/// Default UDP port the incoming CoE listener binds to. TA CMIs emit on 5442.
+const DEFAULT_COE_LISTEN_PORT: u16 = 5442;
+
 /// Mappings for all doors
 #[derive(Debug, PartialEq, Eq)]
 pub struct CmiConfig {
     door_mappings: Vec<DoorMapping>,
+    coe_listen_port: u16,
 }
 impl TryFrom<CmiConfigData> for CmiConfig {
     type Error = PdoZeroError;
@@ -129,37 +143,201 @@ impl TryFrom<CmiConfigData> for CmiConfig {
                 .into_iter()
                 .map(<DoorMappingData as TryInto<DoorMapping>>::try_into)
                 .collect::<Result<Vec<_>, _>>()?,
+            coe_listen_port: value.coe_listen_port.unwrap_or(DEFAULT_COE_LISTEN_PORT),
         })
     }
 }
 impl CmiConfig {
+    /// Re-read `/etc/ta-agi-doorbell/config.toml` and build a fresh [CmiConfig].
+    ///
+    /// Only the CMI portion of the config is live-reloadable; the AGI listen
+    /// address and digest secret are ignored here since they are only read once
+    /// at startup, when the AGI socket and auth layer are set up. Used by the
+    /// `SIGHUP` handler in `main` to hot-swap door mappings.
+    pub fn create() -> Result<Self, ConfigError> {
+        Ok(ConfigData::create()?.cmi.try_into()?)
+    }
+
     pub fn get_cmi_for_door(&self, name: &str) -> Option<&DoorMapping> {
         self.door_mappings.iter().find(|&map| map.door_name == name)
     }
+
+    pub fn door_mappings(&self) -> &[DoorMapping] {
+        &self.door_mappings
+    }
+
+    /// UDP port the incoming CoE listener binds to.
+    ///
+    /// Read once at startup: the listener binds the socket before its receive
+    /// loop, so this field is restart-only and a `SIGHUP` reload does not
+    /// re-bind it (the same deliberate restriction as the AGI socket).
+    pub fn coe_listen_port(&self) -> u16 {
+        self.coe_listen_port
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Deserialize)]
 struct CmiConfigData {
     door_mappings: Vec<DoorMappingData>,
+    /// UDP port the incoming CoE listener binds to. Defaults to [DEFAULT_COE_LISTEN_PORT].
+    coe_listen_port: Option<u16>,
+}
+
+/// Errors that can occur while delivering a CoE packet to a CMI.
+#[derive(Debug)]
+pub enum SendError {
+    Io(std::io::Error),
+    /// A single send attempt did not complete within `send_timeout_ms`.
+    Timeout,
+    /// The payload could not be encoded into a CoE packet.
+    Encode(coe::Error),
+}
+impl From<std::io::Error> for SendError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+impl From<coe::Error> for SendError {
+    fn from(value: coe::Error) -> Self {
+        Self::Encode(value)
+    }
+}
+impl core::fmt::Display for SendError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::Io(x) => write!(f, "Error sending CoE packet: {x}"),
+            Self::Timeout => write!(f, "Timed out sending CoE packet"),
+            Self::Encode(x) => write!(f, "Error encoding CoE packet: {x}"),
+        }
+    }
 }
+impl std::error::Error for SendError {}
 
 /// Mapping a single door to a destination in TA
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DoorMapping {
     pub door_name: String,
     cmi_address: Ipv4Addr,
     cmi_port: u16,
     virtual_node: u8,
     pdo: u8,
+    retries: u32,
+    retry_delay: Duration,
+    send_timeout: Duration,
+    allowed_contexts: Option<Vec<String>>,
+    pulse_duration: Option<Duration>,
 }
 impl DoorMapping {
     pub fn cmi_host(&self) -> String {
         format!("{}:{}", self.cmi_address, self.cmi_port)
     }
 
+    /// The one-based PDO index as entered in the config file.
+    ///
+    /// Internally we store it zero-based (see [DoorMapping]'s `TryFrom`), but the
+    /// config-validation path reports it the way operators wrote it.
+    pub fn pdo_one_based(&self) -> u16 {
+        self.pdo as u16 + 1
+    }
+
+    /// Whether a caller in `context` is allowed to open this door.
+    ///
+    /// Doors without an `allowed_contexts` list stay open to every authenticated
+    /// caller, preserving the previous behaviour.
+    pub fn is_context_allowed(&self, context: &str) -> bool {
+        match &self.allowed_contexts {
+            Some(contexts) => contexts.iter().any(|c| c == context),
+            None => true,
+        }
+    }
+
+    /// The `(cmi_address, cmi_port, virtual_node, pdo)` tuple this door targets.
+    ///
+    /// Used by `--check-config` to detect two doors driving the same output.
+    pub fn target(&self) -> (Ipv4Addr, u16, u8, u8) {
+        (self.cmi_address, self.cmi_port, self.virtual_node, self.pdo)
+    }
+
     pub fn payload_with_value(&self, value: COEValue) -> Payload {
         Payload::new(self.virtual_node, self.pdo, value)
     }
+
+    /// The `(virtual_node, pdo)` pair identifying this door on the CoE bus.
+    ///
+    /// Matches the keys the incoming CoE listener stores, so the door-status
+    /// handler can look up the latest received value for this door.
+    pub fn node_pdo(&self) -> (u8, u8) {
+        (self.virtual_node, self.pdo)
+    }
+
+    /// Send a single CoE [Payload] to this door's CMI, retrying on failure.
+    ///
+    /// Each attempt is wrapped in a `send_timeout`; on timeout or I/O error we
+    /// back off for `retry_delay` and try again, up to `retries` extra times.
+    /// Only the last error is returned once every attempt has failed.
+    ///
+    /// CoE is a one-way fire-and-forget protocol, so this cannot confirm the CMI
+    /// actually applied the value -- there is no reply to wait for. What the
+    /// retry/timeout machinery does catch is local failures to bind, connect
+    /// (e.g. no route) or send, and sends that do not finish within the timeout.
+    /// ICMP errors (host/port unreachable) that arrive after the datagram has
+    /// left are not seen, because each attempt's socket is dropped right after
+    /// its send. Operators should read a successful send as "the datagram left
+    /// the host", not as delivery.
+    pub async fn send_value(&self, value: COEValue) -> Result<(), SendError> {
+        let packet = Packet::try_from(vec![self.payload_with_value(value)])?;
+        let bytes = Vec::<u8>::try_from(&packet)?;
+        let host = self.cmi_host();
+
+        let mut last_error = None;
+        for attempt in 0..=self.retries {
+            match timeout(self.send_timeout, self.send_once(&host, &bytes)).await {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(e)) => last_error = Some(SendError::Io(e)),
+                Err(_) => last_error = Some(SendError::Timeout),
+            }
+            if attempt < self.retries {
+                warn!(
+                    "Sending CoE packet to door {} failed (attempt {}/{}), retrying",
+                    self.door_name,
+                    attempt + 1,
+                    self.retries + 1,
+                );
+                sleep(self.retry_delay).await;
+            }
+        }
+        Err(last_error.expect("the loop runs at least once and only breaks on success"))
+    }
+
+    /// Send the ON value to this door's CMI, retrying on failure.
+    pub async fn open_door(&self) -> Result<(), SendError> {
+        self.send_value(COEValue::Digital(true)).await
+    }
+
+    /// Send the OFF value to this door's CMI, retrying on failure.
+    ///
+    /// Used by pulse mode to release a momentary door strike after
+    /// [DoorMapping::pulse_duration].
+    pub async fn close_door(&self) -> Result<(), SendError> {
+        self.send_value(COEValue::Digital(false)).await
+    }
+
+    /// How long ON is held before OFF is sent, for momentary strikes.
+    ///
+    /// `None` means latching ON only (unchanged behaviour).
+    pub fn pulse_duration(&self) -> Option<Duration> {
+        self.pulse_duration
+    }
+
+    async fn send_once(&self, host: &str, bytes: &[u8]) -> Result<(), std::io::Error> {
+        // A fresh socket per attempt: it is dropped after the single `send`, so
+        // only errors raised by `connect` or `send` themselves are returned; an
+        // ICMP error that comes back later is never read.
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(host).await?;
+        socket.send(bytes).await?;
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -181,6 +359,15 @@ impl TryFrom<DoorMappingData> for DoorMapping {
             cmi_port: value.cmi_port.unwrap_or(5442),
             virtual_node: value.virtual_node,
             pdo: value.pdo.checked_sub(1).ok_or(PdoZeroError {})?,
+            retries: value.retries.unwrap_or(DEFAULT_RETRIES),
+            retry_delay: Duration::from_millis(
+                value.retry_delay_ms.unwrap_or(DEFAULT_RETRY_DELAY_MS),
+            ),
+            send_timeout: Duration::from_millis(
+                value.send_timeout_ms.unwrap_or(DEFAULT_SEND_TIMEOUT_MS),
+            ),
+            allowed_contexts: value.allowed_contexts,
+            pulse_duration: value.pulse_duration_ms.map(Duration::from_millis),
         })
     }
 }
@@ -193,4 +380,14 @@ struct DoorMappingData {
     cmi_port: Option<u16>,
     virtual_node: u8,
     pdo: u8,
+    /// Extra send attempts after the first failure. Defaults to [DEFAULT_RETRIES].
+    retries: Option<u32>,
+    /// Backoff between attempts, in milliseconds. Defaults to [DEFAULT_RETRY_DELAY_MS].
+    retry_delay_ms: Option<u64>,
+    /// Per-attempt send timeout, in milliseconds. Defaults to [DEFAULT_SEND_TIMEOUT_MS].
+    send_timeout_ms: Option<u64>,
+    /// Asterisk dialplan contexts allowed to open this door. `None` allows all.
+    allowed_contexts: Option<Vec<String>>,
+    /// When set, send OFF this many milliseconds after ON for a momentary pulse.
+    pulse_duration_ms: Option<u64>,
 }
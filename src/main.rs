@@ -1,13 +1,21 @@
-use blazing_agi::{command::{verbose::Verbose, AGIResponse, GetFullVariable}, connection::Connection, handler::AGIHandler, router::Router, serve, AGIError, AGIRequest};
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use coe::COEValue;
+use blazing_agi::{command::{setvariable::SetVariable, verbose::Verbose, AGIResponse, GetFullVariable}, connection::Connection, handler::AGIHandler, router::Router, serve, AGIError, AGIRequest};
 use blazing_agi_macros::layer_before;
 use rand::Rng;
 use sha1::{Digest, Sha1};
 use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
 use tracing::{debug, info, level_filters::LevelFilter, warn};
 use tracing_subscriber::{fmt::format::FmtSpan, prelude::*, EnvFilter};
 
+mod check_config;
+mod coe_listener;
 mod config;
-use config::{CmiConfig, Config, DoorMapping};
+use coe_listener::SharedDoorState;
+use config::{CmiConfig, Config};
 
 
 
@@ -109,13 +117,39 @@ impl AGIHandler for SHA1DigestOverAGI {
 }
 
 
+/// Fetch a dialplan variable/expression via `GetFullVariable`.
+///
+/// Returns `Ok(None)` when Asterisk reports the variable as unset, and maps any
+/// non-200 response to an [AGIError] the way the other handlers do.
+async fn get_full_variable(
+    connection: &mut Connection,
+    expression: &str,
+) -> Result<Option<String>, AGIError> {
+    match connection
+        .send_command(GetFullVariable::new(expression.to_string()))
+        .await?
+    {
+        AGIResponse::Ok(inner) => Ok(inner.value),
+        m => Err(AGIError::Not200(m.into())),
+    }
+}
+
 #[derive(Debug)]
 struct OpenDoorHandler {
-    config: CmiConfig,
+    // The CMI portion of the config is reloaded on `SIGHUP`, so we hold a shared
+    // `ArcSwap` and load a snapshot per request rather than a fixed `CmiConfig`.
+    config: Arc<ArcSwap<CmiConfig>>,
 }
 impl OpenDoorHandler {
-    fn get_cmi_for_door<S: AsRef<str>>(&self, door_name: S) -> Option<&DoorMapping> {
-        self.config.get_cmi_for_door(door_name.as_ref())
+    /// Load the currently active [CmiConfig] snapshot as an owned `Arc`.
+    ///
+    /// Every request reads through this so that a `SIGHUP` reload is picked up
+    /// without restarting the service. We return the owned `Arc` from
+    /// `load_full` rather than an `arc_swap::Guard`, since the snapshot is held
+    /// across `.await` points (the `GetFullVariable` round-trip to Asterisk) and
+    /// a live guard there would pin a hazard slot and stall reloads.
+    fn current_config(&self) -> Arc<CmiConfig> {
+        self.config.load_full()
     }
 }
 #[async_trait::async_trait]
@@ -124,16 +158,106 @@ impl AGIHandler for OpenDoorHandler {
         debug!("Got new AGI request to the open_door handler.");
         // make sure the door is known
         let door = request.captures.get("door").ok_or(AGIError::ClientSideError("Got no captured door".to_owned()))?;
-        // get the cmi connection used for this door
-        connection.send_command(Verbose::new(format!("The door {door} is not known."))).await?;
-        let cmi_config = self.get_cmi_for_door(door).ok_or(AGIError::ClientSideError("Door is not known.".to_owned()))?;
+        // get the cmi connection used for this door, from the live config snapshot
+        let config = self.current_config();
+        let cmi_config = config.get_cmi_for_door(door).ok_or_else(|| {
+            AGIError::ClientSideError("Door is not known.".to_owned())
+        })?;
+        // authorize the calling context against this door's allow list
+        let context = get_full_variable(connection, "${CONTEXT}").await?.unwrap_or_default();
+        if !cmi_config.is_context_allowed(&context) {
+            warn!(
+                "Caller in context {context} is not allowed to open door {}",
+                cmi_config.door_name
+            );
+            connection
+                .send_command(Verbose::new(format!(
+                    "Context {context} is not allowed to open this door."
+                )))
+                .await?;
+            return Err(AGIError::ClientSideError(
+                "Caller is not allowed to open this door.".to_owned(),
+            ));
+        }
         // send ON to that CMI
         cmi_config.open_door().await.map_err(|x| AGIError::ClientSideError(x.to_string()))?;
         info!("Sent CoE packet to open Door {}", cmi_config.door_name);
+        // in pulse mode, release the strike after the configured delay without
+        // blocking the AGI response for the pulse window.
+        if let Some(pulse) = cmi_config.pulse_duration() {
+            let door = cmi_config.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(pulse).await;
+                if let Err(e) = door.close_door().await {
+                    warn!("Failed to send pulse OFF for door {}: {e}", door.door_name);
+                }
+            });
+        }
         Ok(())
     }
 }
 
+#[derive(Debug)]
+struct DoorStatusHandler {
+    config: Arc<ArcSwap<CmiConfig>>,
+    state: SharedDoorState,
+}
+#[async_trait::async_trait]
+impl AGIHandler for DoorStatusHandler {
+    async fn handle(&self, connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+        debug!("Got new AGI request to the door_status handler.");
+        let door = request.captures.get("door").ok_or(AGIError::ClientSideError("Got no captured door".to_owned()))?;
+        // hold an owned snapshot, not an `arc_swap::Guard`: the guard would be
+        // kept alive across the `state.read().await` and `send_command` awaits.
+        let config = self.config.load_full();
+        let cmi_config = config.get_cmi_for_door(door).ok_or_else(|| {
+            AGIError::ClientSideError("Door is not known.".to_owned())
+        })?;
+        // report the latest value the CoE listener has seen for this door as a
+        // stable dialplan token, or "unknown" when no packet has arrived yet (or
+        // the cached value is not a digital open/closed state).
+        let status = match self.state.read().await.get(&cmi_config.node_pdo()) {
+            Some(COEValue::Digital(true)) => "open",
+            Some(COEValue::Digital(false)) => "closed",
+            Some(_) | None => "unknown",
+        };
+        connection
+            .send_command(SetVariable::new("DOOR_STATUS".to_string(), status.to_string()))
+            .await?;
+        connection
+            .send_command(Verbose::new(format!("Door {door} status: {status}")))
+            .await?;
+        Ok(())
+    }
+}
+
+/// Listen for `SIGHUP` and hot-reload the CMI portion of the config.
+///
+/// On a parse error or a `PdoZero` failure we keep the previously loaded config
+/// and only log a warning; the new [CmiConfig] is swapped in atomically when
+/// [CmiConfig::create] succeeds.
+async fn reload_on_sighup(config: Arc<ArcSwap<CmiConfig>>) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Could not install SIGHUP handler, door mappings will not hot-reload: {e}");
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        info!("Got SIGHUP, reloading door mappings");
+        match CmiConfig::create() {
+            Ok(new_cmi) => {
+                config.store(Arc::new(new_cmi));
+                info!("Reloaded door mappings successfully");
+            }
+            Err(e) => {
+                warn!("Could not reload door mappings, keeping the previous config: {e}");
+            }
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // create the logger
@@ -147,15 +271,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
     tracing::subscriber::set_global_default(subscriber).expect("static tracing config");
 
+    // In --check-config mode, validate the config and exit without binding.
+    if std::env::args().skip(1).any(|arg| arg == "--check-config") {
+        std::process::exit(check_config::check_config().await);
+    }
+
     // setup config
     let config = Config::create()?;
     let digest_secret = config.agi_digest_secret();
     let agi_listen_string = config.agi_listen_string();
     debug!("Successfully created the config");
 
+    // The CMI config is hot-reloadable on SIGHUP, so share it behind an ArcSwap.
+    let cmi = Arc::new(ArcSwap::from_pointee(config.cmi));
+    tokio::spawn(reload_on_sighup(cmi.clone()));
+
+    // Spawn the incoming CoE listener so we can report door/sensor state.
+    // The listener binds the socket once at startup, so `coe_listen_port` is
+    // restart-only -- like the AGI socket, a SIGHUP does not re-bind it.
+    let door_state = coe_listener::new_state();
+    let coe_listen_port = cmi.load().coe_listen_port();
+    tokio::spawn(coe_listener::listen(coe_listen_port, door_state.clone()));
+
     // Create the router from the handlers you have defined
     let router = Router::new()
-        .route("/open_door/:door", OpenDoorHandler { config: config.cmi })
+        .route("/open_door/:door", OpenDoorHandler { config: cmi.clone() })
+        .route(
+            "/door_status/:door",
+            DoorStatusHandler {
+                config: cmi.clone(),
+                state: door_state.clone(),
+            },
+        )
         .layer(layer_before!(SHA1DigestOverAGI::new(digest_secret)));
 
     let listener = TcpListener::bind(agi_listen_string).await?;